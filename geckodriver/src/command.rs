@@ -7,8 +7,8 @@ use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use uuid::Uuid;
-use webdriver::command::{WebDriverCommand, WebDriverExtensionCommand};
-use webdriver::common::WebElement;
+use webdriver::command::{LocatorParameters, WebDriverCommand, WebDriverExtensionCommand};
+use webdriver::common::{ShadowRoot, WebElement};
 use webdriver::error::{ErrorStatus, WebDriverError, WebDriverResult};
 use webdriver::httpapi::WebDriverExtensionRoute;
 use webdriver::Parameters;
@@ -57,6 +57,56 @@ pub fn extension_routes() -> Vec<(Method, &'static str, GeckoExtensionRoute)> {
             "/session/{sessionId}/moz/print",
             GeckoExtensionRoute::Print,
         ),
+        (
+            Method::POST,
+            "/session/{sessionId}/webauthn/authenticator",
+            GeckoExtensionRoute::AddVirtualAuthenticator,
+        ),
+        (
+            Method::DELETE,
+            "/session/{sessionId}/webauthn/authenticator/{authenticatorId}",
+            GeckoExtensionRoute::RemoveVirtualAuthenticator,
+        ),
+        (
+            Method::POST,
+            "/session/{sessionId}/webauthn/authenticator/{authenticatorId}/credential",
+            GeckoExtensionRoute::AddCredential,
+        ),
+        (
+            Method::GET,
+            "/session/{sessionId}/webauthn/authenticator/{authenticatorId}/credentials",
+            GeckoExtensionRoute::GetCredentials,
+        ),
+        (
+            Method::DELETE,
+            "/session/{sessionId}/webauthn/authenticator/{authenticatorId}/credentials",
+            GeckoExtensionRoute::RemoveAllCredentials,
+        ),
+        (
+            Method::DELETE,
+            "/session/{sessionId}/webauthn/authenticator/{authenticatorId}/credentials/{credentialId}",
+            GeckoExtensionRoute::RemoveCredential,
+        ),
+        (
+            Method::POST,
+            "/session/{sessionId}/webauthn/authenticator/{authenticatorId}/uv",
+            GeckoExtensionRoute::SetUserVerified,
+        ),
+        (
+            Method::POST,
+            "/session/{sessionId}/moz/element/{elementId}/shadow",
+            GeckoExtensionRoute::GetShadowRoot,
+        ),
+        (
+            Method::POST,
+            "/session/{sessionId}/moz/shadow/{shadowId}/element",
+            GeckoExtensionRoute::FindShadowRootElement,
+        ),
+        (
+            Method::POST,
+            "/session/{sessionId}/moz/shadow/{shadowId}/elements",
+            GeckoExtensionRoute::FindShadowRootElements,
+        ),
     ];
 }
 
@@ -70,6 +120,16 @@ pub enum GeckoExtensionRoute {
     UninstallAddon,
     TakeFullScreenshot,
     Print,
+    AddVirtualAuthenticator,
+    RemoveVirtualAuthenticator,
+    AddCredential,
+    GetCredentials,
+    RemoveCredential,
+    RemoveAllCredentials,
+    SetUserVerified,
+    GetShadowRoot,
+    FindShadowRootElement,
+    FindShadowRootElements,
 }
 
 impl WebDriverExtensionRoute for GeckoExtensionRoute {
@@ -115,6 +175,104 @@ impl WebDriverExtensionRoute for GeckoExtensionRoute {
             }
             TakeFullScreenshot => GeckoExtensionCommand::TakeFullScreenshot,
             Print => GeckoExtensionCommand::Print(serde_json::from_value(body_data.clone())?),
+            AddVirtualAuthenticator => GeckoExtensionCommand::AddVirtualAuthenticator(
+                serde_json::from_value(body_data.clone())?,
+            ),
+            RemoveVirtualAuthenticator => {
+                let authenticator_id = try_opt!(
+                    params.get("authenticatorId"),
+                    ErrorStatus::InvalidArgument,
+                    "Missing authenticatorId parameter"
+                );
+                GeckoExtensionCommand::RemoveVirtualAuthenticator(
+                    authenticator_id.as_str().to_string(),
+                )
+            }
+            AddCredential => {
+                let authenticator_id = try_opt!(
+                    params.get("authenticatorId"),
+                    ErrorStatus::InvalidArgument,
+                    "Missing authenticatorId parameter"
+                );
+                GeckoExtensionCommand::AddCredential(
+                    authenticator_id.as_str().to_string(),
+                    serde_json::from_value(body_data.clone())?,
+                )
+            }
+            GetCredentials => {
+                let authenticator_id = try_opt!(
+                    params.get("authenticatorId"),
+                    ErrorStatus::InvalidArgument,
+                    "Missing authenticatorId parameter"
+                );
+                GeckoExtensionCommand::GetCredentials(authenticator_id.as_str().to_string())
+            }
+            RemoveAllCredentials => {
+                let authenticator_id = try_opt!(
+                    params.get("authenticatorId"),
+                    ErrorStatus::InvalidArgument,
+                    "Missing authenticatorId parameter"
+                );
+                GeckoExtensionCommand::RemoveAllCredentials(authenticator_id.as_str().to_string())
+            }
+            RemoveCredential => {
+                let authenticator_id = try_opt!(
+                    params.get("authenticatorId"),
+                    ErrorStatus::InvalidArgument,
+                    "Missing authenticatorId parameter"
+                );
+                let credential_id = try_opt!(
+                    params.get("credentialId"),
+                    ErrorStatus::InvalidArgument,
+                    "Missing credentialId parameter"
+                );
+                GeckoExtensionCommand::RemoveCredential(
+                    authenticator_id.as_str().to_string(),
+                    credential_id.as_str().to_string(),
+                )
+            }
+            SetUserVerified => {
+                let authenticator_id = try_opt!(
+                    params.get("authenticatorId"),
+                    ErrorStatus::InvalidArgument,
+                    "Missing authenticatorId parameter"
+                );
+                GeckoExtensionCommand::SetUserVerified(
+                    authenticator_id.as_str().to_string(),
+                    serde_json::from_value(body_data.clone())?,
+                )
+            }
+            GetShadowRoot => {
+                let element_id = try_opt!(
+                    params.get("elementId"),
+                    ErrorStatus::InvalidArgument,
+                    "Missing elementId parameter"
+                );
+                let element = WebElement(element_id.as_str().to_string());
+                GeckoExtensionCommand::GetShadowRoot(element)
+            }
+            FindShadowRootElement => {
+                let shadow_id = try_opt!(
+                    params.get("shadowId"),
+                    ErrorStatus::InvalidArgument,
+                    "Missing shadowId parameter"
+                );
+                GeckoExtensionCommand::FindShadowRootElement(
+                    ShadowRoot(shadow_id.as_str().to_string()),
+                    serde_json::from_value(body_data.clone())?,
+                )
+            }
+            FindShadowRootElements => {
+                let shadow_id = try_opt!(
+                    params.get("shadowId"),
+                    ErrorStatus::InvalidArgument,
+                    "Missing shadowId parameter"
+                );
+                GeckoExtensionCommand::FindShadowRootElements(
+                    ShadowRoot(shadow_id.as_str().to_string()),
+                    serde_json::from_value(body_data.clone())?,
+                )
+            }
         };
 
         Ok(WebDriverCommand::Extension(command))
@@ -131,6 +289,16 @@ pub enum GeckoExtensionCommand {
     UninstallAddon(AddonUninstallParameters),
     TakeFullScreenshot,
     Print(PrintParameters),
+    AddVirtualAuthenticator(AuthenticatorParameters),
+    RemoveVirtualAuthenticator(String),
+    AddCredential(String, CredentialParameters),
+    GetCredentials(String),
+    RemoveCredential(String, String),
+    RemoveAllCredentials(String),
+    SetUserVerified(String, SetUserVerifiedParameters),
+    GetShadowRoot(WebElement),
+    FindShadowRootElement(ShadowRoot, LocatorParameters),
+    FindShadowRootElements(ShadowRoot, LocatorParameters),
 }
 
 impl WebDriverExtensionCommand for GeckoExtensionCommand {
@@ -145,6 +313,16 @@ impl WebDriverExtensionCommand for GeckoExtensionCommand {
             XblAnonymousChildren(_) => None,
             TakeFullScreenshot => None,
             Print(x) => Some(serde_json::to_value(x).unwrap()),
+            AddVirtualAuthenticator(x) => Some(serde_json::to_value(x).unwrap()),
+            RemoveVirtualAuthenticator(_) => None,
+            AddCredential(_, x) => Some(serde_json::to_value(x).unwrap()),
+            GetCredentials(_) => None,
+            RemoveCredential(_, _) => None,
+            RemoveAllCredentials(_) => None,
+            SetUserVerified(_, x) => Some(serde_json::to_value(x).unwrap()),
+            GetShadowRoot(_) => None,
+            FindShadowRootElement(_, x) => Some(serde_json::to_value(x).unwrap()),
+            FindShadowRootElements(_, x) => Some(serde_json::to_value(x).unwrap()),
         }
     }
 }
@@ -153,6 +331,8 @@ impl WebDriverExtensionCommand for GeckoExtensionCommand {
 pub struct AddonInstallParameters {
     pub path: String,
     pub temporary: Option<bool>,
+    #[serde(rename = "allowPrivateBrowsing")]
+    pub allow_private_browsing: Option<bool>,
 }
 
 impl<'de> Deserialize<'de> for AddonInstallParameters {
@@ -165,6 +345,8 @@ impl<'de> Deserialize<'de> for AddonInstallParameters {
         struct Base64 {
             addon: String,
             temporary: Option<bool>,
+            #[serde(rename = "allowPrivateBrowsing")]
+            allow_private_browsing: Option<bool>,
         };
 
         #[derive(Debug, Deserialize)]
@@ -172,6 +354,8 @@ impl<'de> Deserialize<'de> for AddonInstallParameters {
         struct Path {
             path: String,
             temporary: Option<bool>,
+            #[serde(rename = "allowPrivateBrowsing")]
+            allow_private_browsing: Option<bool>,
         };
 
         #[derive(Debug, Deserialize)]
@@ -185,6 +369,7 @@ impl<'de> Deserialize<'de> for AddonInstallParameters {
             Helper::Path(ref mut data) => AddonInstallParameters {
                 path: data.path.clone(),
                 temporary: data.temporary,
+                allow_private_browsing: data.allow_private_browsing,
             },
             Helper::Base64(ref mut data) => {
                 let content = base64::decode(&data.addon).map_err(de::Error::custom)?;
@@ -205,6 +390,7 @@ impl<'de> Deserialize<'de> for AddonInstallParameters {
                 AddonInstallParameters {
                     path,
                     temporary: data.temporary,
+                    allow_private_browsing: data.allow_private_browsing,
                 }
             }
         };
@@ -241,6 +427,95 @@ pub struct LogOptions {
     pub level: Option<logging::Level>,
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorParameters {
+    pub protocol: AuthenticatorProtocol,
+    pub transport: AuthenticatorTransport,
+    pub has_resident_key: bool,
+    pub has_user_verification: bool,
+    pub is_user_consenting: bool,
+    pub is_user_verified: bool,
+}
+
+impl<'de> Deserialize<'de> for AuthenticatorParameters {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Debug, Deserialize)]
+        #[serde(rename_all = "camelCase", deny_unknown_fields)]
+        struct Raw {
+            protocol: AuthenticatorProtocol,
+            transport: AuthenticatorTransport,
+            has_resident_key: bool,
+            has_user_verification: bool,
+            #[serde(default = "default_is_user_consenting")]
+            is_user_consenting: bool,
+            is_user_verified: bool,
+        }
+
+        let raw = Raw::deserialize(deserializer)?;
+        if raw.is_user_verified && !raw.has_user_verification {
+            return Err(de::Error::custom(
+                "isUserVerified requires hasUserVerification to be true",
+            ));
+        }
+
+        Ok(AuthenticatorParameters {
+            protocol: raw.protocol,
+            transport: raw.transport,
+            has_resident_key: raw.has_resident_key,
+            has_user_verification: raw.has_user_verification,
+            is_user_consenting: raw.is_user_consenting,
+            is_user_verified: raw.is_user_verified,
+        })
+    }
+}
+
+fn default_is_user_consenting() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AuthenticatorProtocol {
+    #[serde(rename = "ctap1/U2F")]
+    Ctap1U2f,
+    #[serde(rename = "ctap2")]
+    Ctap2,
+    #[serde(rename = "ctap2_1")]
+    Ctap21,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthenticatorTransport {
+    Usb,
+    Nfc,
+    Ble,
+    #[serde(rename = "smart-card")]
+    SmartCard,
+    Hybrid,
+    Internal,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialParameters {
+    pub credential_id: String,
+    pub is_resident_credential: bool,
+    pub rp_id: String,
+    pub private_key: String,
+    pub user_handle: Option<String>,
+    pub sign_count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetUserVerifiedParameters {
+    pub is_user_verified: bool,
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(default, rename_all = "camelCase")]
 pub struct PrintParameters {
@@ -281,12 +556,9 @@ impl Default for PrintOrientation {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-#[serde(default)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct PrintPage {
-    #[serde(deserialize_with = "deserialize_to_positive_f64")]
     pub width: f64,
-    #[serde(deserialize_with = "deserialize_to_positive_f64")]
     pub height: f64,
 }
 
@@ -299,6 +571,54 @@ impl Default for PrintPage {
     }
 }
 
+impl<'de> Deserialize<'de> for PrintPage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Debug, Default, Deserialize)]
+        #[serde(default, deny_unknown_fields)]
+        struct Raw {
+            format: Option<String>,
+            #[serde(deserialize_with = "deserialize_to_optional_positive_f64")]
+            width: Option<f64>,
+            #[serde(deserialize_with = "deserialize_to_optional_positive_f64")]
+            height: Option<f64>,
+        }
+
+        let raw = Raw::deserialize(deserializer)?;
+        let default = PrintPage::default();
+        let (mut width, mut height) = (default.width, default.height);
+
+        if let Some(format) = &raw.format {
+            let (format_width, format_height) =
+                page_format_dimensions(format).map_err(de::Error::custom)?;
+            width = format_width;
+            height = format_height;
+        }
+
+        if let Some(value) = raw.width {
+            width = value;
+        }
+        if let Some(value) = raw.height {
+            height = value;
+        }
+
+        Ok(PrintPage { width, height })
+    }
+}
+
+fn page_format_dimensions(format: &str) -> Result<(f64, f64), String> {
+    match format {
+        "A3" => Ok((29.7, 42.0)),
+        "A4" => Ok((21.0, 29.7)),
+        "Letter" => Ok((21.59, 27.94)),
+        "Legal" => Ok((21.59, 35.56)),
+        "Tabloid" => Ok((27.94, 43.18)),
+        other => Err(format!("unknown page format: {}", other)),
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(default)]
 pub struct PrintMargins {
@@ -334,6 +654,19 @@ where
     Ok(val)
 }
 
+fn deserialize_to_optional_positive_f64<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let val = Option::<f64>::deserialize(deserializer)?;
+    if let Some(val) = val {
+        if val < 0.0 {
+            return Err(de::Error::custom(format!("{} is negative", val)));
+        }
+    }
+    Ok(val)
+}
+
 fn deserialize_to_print_scale_f64<'de, D>(deserializer: D) -> Result<f64, D::Error>
 where
     D: Deserializer<'de>,
@@ -364,6 +697,7 @@ mod tests {
         let params = AddonInstallParameters {
             path: "/path/to.xpi".to_string(),
             temporary: Some(true),
+            allow_private_browsing: None,
         };
         assert_de(&params, json!({"path": "/path/to.xpi", "temporary": true}));
     }
@@ -373,10 +707,30 @@ mod tests {
         let params = AddonInstallParameters {
             path: "/path/to.xpi".to_string(),
             temporary: None,
+            allow_private_browsing: None,
         };
         assert_de(&params, json!({"path": "/path/to.xpi"}));
     }
 
+    #[test]
+    fn test_json_addon_install_parameters_with_path_and_allow_private_browsing() {
+        let params = AddonInstallParameters {
+            path: "/path/to.xpi".to_string(),
+            temporary: Some(true),
+            allow_private_browsing: Some(true),
+        };
+        assert_de(
+            &params,
+            json!({"path": "/path/to.xpi", "temporary": true, "allowPrivateBrowsing": true}),
+        );
+    }
+
+    #[test]
+    fn test_json_addon_install_parameters_with_path_allow_private_browsing_invalid_type() {
+        let json = json!({"path": "/path/to.xpi", "allowPrivateBrowsing": "foo"});
+        assert!(serde_json::from_value::<AddonInstallParameters>(json).is_err());
+    }
+
     #[test]
     fn test_json_addon_install_parameters_with_path_invalid_type() {
         let json = json!({"path": true, "temporary": true});
@@ -425,6 +779,24 @@ mod tests {
         assert!(serde_json::from_value::<AddonInstallParameters>(json).is_err());
     }
 
+    #[test]
+    fn test_json_addon_install_parameters_with_addon_and_allow_private_browsing() {
+        let json = json!({"addon": "aGVsbG8=", "allowPrivateBrowsing": true});
+        let data = serde_json::from_value::<AddonInstallParameters>(json).unwrap();
+
+        assert_eq!(data.allow_private_browsing, Some(true));
+        let mut file = File::open(data.path).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "hello");
+    }
+
+    #[test]
+    fn test_json_addon_install_parameters_with_addon_allow_private_browsing_invalid_type() {
+        let json = json!({"addon": "aGVsbG8=", "allowPrivateBrowsing": "foo"});
+        assert!(serde_json::from_value::<AddonInstallParameters>(json).is_err());
+    }
+
     #[test]
     fn test_json_install_parameters_with_temporary_only() {
         let json = json!({"temporary": true});
@@ -535,4 +907,144 @@ mod tests {
     fn test_json_gecko_scale_invalid() {
         assert!(serde_json::from_value::<AddonInstallParameters>(json!({"scale": 3})).is_err());
     }
+
+    #[test]
+    fn test_json_gecko_print_page_format_a4() {
+        let params = PrintPage {
+            width: 21.0,
+            height: 29.7,
+        };
+        assert_de(&params, json!({"format": "A4"}));
+    }
+
+    #[test]
+    fn test_json_gecko_print_page_format_overridden_by_explicit_dimensions() {
+        let params = PrintPage {
+            width: 10.0,
+            height: 29.7,
+        };
+        assert_de(&params, json!({"format": "A4", "width": 10.0}));
+    }
+
+    #[test]
+    fn test_json_gecko_print_page_format_invalid() {
+        let json = json!({"format": "B5"});
+        assert!(serde_json::from_value::<PrintPage>(json).is_err());
+    }
+
+    #[test]
+    fn test_json_authenticator_parameters() {
+        let params = AuthenticatorParameters {
+            protocol: AuthenticatorProtocol::Ctap2,
+            transport: AuthenticatorTransport::Internal,
+            has_resident_key: true,
+            has_user_verification: true,
+            is_user_consenting: true,
+            is_user_verified: true,
+        };
+        assert_de(
+            &params,
+            json!({
+                "protocol": "ctap2",
+                "transport": "internal",
+                "hasResidentKey": true,
+                "hasUserVerification": true,
+                "isUserVerified": true,
+            }),
+        );
+    }
+
+    #[test]
+    fn test_json_authenticator_parameters_default_is_user_consenting() {
+        let json = json!({
+            "protocol": "ctap1/U2F",
+            "transport": "usb",
+            "hasResidentKey": false,
+            "hasUserVerification": false,
+            "isUserVerified": false,
+        });
+        let data = serde_json::from_value::<AuthenticatorParameters>(json).unwrap();
+        assert_eq!(data.is_user_consenting, true);
+    }
+
+    #[test]
+    fn test_json_authenticator_parameters_invalid_protocol() {
+        let json = json!({
+            "protocol": "ctap3",
+            "transport": "usb",
+            "hasResidentKey": false,
+            "hasUserVerification": false,
+            "isUserVerified": false,
+        });
+        assert!(serde_json::from_value::<AuthenticatorParameters>(json).is_err());
+    }
+
+    #[test]
+    fn test_json_authenticator_parameters_invalid_transport() {
+        let json = json!({
+            "protocol": "ctap2",
+            "transport": "bluetooth",
+            "hasResidentKey": false,
+            "hasUserVerification": false,
+            "isUserVerified": false,
+        });
+        assert!(serde_json::from_value::<AuthenticatorParameters>(json).is_err());
+    }
+
+    #[test]
+    fn test_json_authenticator_parameters_user_verified_without_support() {
+        let json = json!({
+            "protocol": "ctap2",
+            "transport": "usb",
+            "hasResidentKey": false,
+            "hasUserVerification": false,
+            "isUserVerified": true,
+        });
+        assert!(serde_json::from_value::<AuthenticatorParameters>(json).is_err());
+    }
+
+    #[test]
+    fn test_json_credential_parameters() {
+        let params = CredentialParameters {
+            credential_id: "AAEC".to_string(),
+            is_resident_credential: true,
+            rp_id: "example.com".to_string(),
+            private_key: "MIIBVg==".to_string(),
+            user_handle: Some("AAEC".to_string()),
+            sign_count: 0,
+        };
+        assert_de(
+            &params,
+            json!({
+                "credentialId": "AAEC",
+                "isResidentCredential": true,
+                "rpId": "example.com",
+                "privateKey": "MIIBVg==",
+                "userHandle": "AAEC",
+                "signCount": 0,
+            }),
+        );
+    }
+
+    #[test]
+    fn test_json_credential_parameters_no_user_handle() {
+        let params = CredentialParameters {
+            credential_id: "AAEC".to_string(),
+            is_resident_credential: false,
+            rp_id: "example.com".to_string(),
+            private_key: "MIIBVg==".to_string(),
+            user_handle: None,
+            sign_count: 1,
+        };
+        assert_de(
+            &params,
+            json!({
+                "credentialId": "AAEC",
+                "isResidentCredential": false,
+                "rpId": "example.com",
+                "privateKey": "MIIBVg==",
+                "signCount": 1,
+            }),
+        );
+    }
 }